@@ -0,0 +1,97 @@
+/*
+ * Author: Dylan Turner
+ * Description: Population/generation driver that runs genetic evolution over Networks
+ */
+
+use rand::{
+    Rng, thread_rng
+};
+use rayon::prelude::*;
+use crate::network::Network;
+
+// Best/mean fitness recorded for a single generation
+#[derive(Debug, Clone, Copy)]
+pub struct GenerationStats {
+    pub best_fitness: f64,
+    pub mean_fitness: f64
+}
+
+// A collection of Networks evolved together across generations
+#[derive(Debug, Clone)]
+pub struct Population {
+    pub members: Vec<Network>
+}
+
+impl Population {
+    pub fn new(members: Vec<Network>) -> Self {
+        Self {
+            members
+        }
+    }
+
+    // Evaluate every member against a fixed dataset concurrently across CPU cores, by fanning
+    // each member's own rayon batch out across the pool as well
+    pub fn evaluate_batch(&self, inputs: &[Vec<u8>]) -> Vec<Vec<Vec<u8>>> {
+        self.members.par_iter()
+            .map(|member| member.result_batch(inputs))
+            .collect()
+    }
+
+    // Run `generations` rounds of scoring, elitism and tournament-selected crossover/mutation
+    pub async fn evolve<F>(
+            &mut self, fitness: F, generations: usize,
+            elite: usize, tournament_k: usize) -> Vec<GenerationStats>
+            where F: Fn(&Network) -> f64 + Sync {
+        let mut history = Vec::new();
+        for _ in 0..generations {
+            let scores = Self::score_all(&self.members, &fitness);
+
+            let mut ranked: Vec<usize> = (0..self.members.len()).collect();
+            ranked.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap());
+
+            history.push(GenerationStats {
+                best_fitness: scores[ranked[0]],
+                mean_fitness: scores.iter().sum::<f64>() / scores.len() as f64
+            });
+
+            let mut next_gen = Vec::with_capacity(self.members.len());
+            for &i in ranked.iter().take(elite) {
+                next_gen.push(self.members[i].clone());
+            }
+            while next_gen.len() < self.members.len() {
+                let mut parent_a = Self::tournament_select(
+                    &self.members, &scores, tournament_k
+                ).clone();
+                let mut parent_b = Self::tournament_select(
+                    &self.members, &scores, tournament_k
+                ).clone();
+                parent_a.random_trade(&mut parent_b).await;
+                parent_a.mutate().await;
+                next_gen.push(parent_a);
+            }
+
+            self.members = next_gen;
+        }
+        history
+    }
+
+    // Score every member concurrently across CPU cores with rayon
+    fn score_all<F>(members: &[Network], fitness: &F) -> Vec<f64>
+            where F: Fn(&Network) -> f64 + Sync {
+        members.par_iter().map(fitness).collect()
+    }
+
+    // Pick tournament_k random members and keep the fittest
+    fn tournament_select<'a>(
+            members: &'a [Network], scores: &[f64], tournament_k: usize) -> &'a Network {
+        let mut rng = thread_rng();
+        let mut best_ind = rng.gen_range(0..members.len());
+        for _ in 1..tournament_k {
+            let candidate = rng.gen_range(0..members.len());
+            if scores[candidate] > scores[best_ind] {
+                best_ind = candidate;
+            }
+        }
+        &members[best_ind]
+    }
+}
@@ -0,0 +1,3 @@
+pub mod neuron;
+pub mod network;
+pub mod evolution;
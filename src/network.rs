@@ -3,23 +3,24 @@
  * Description: Neural Network that can be used to predict a game
  */
 
-use std::{
-    fs::{
-        File, remove_file
-    }, io::{
-        Write, Read
-    }, path::Path
+use std::fs;
+use futures::{
+    executor::block_on,
+    future::try_join_all
+};
+use rayon::prelude::*;
+use serde::{
+    Serialize, Deserialize
 };
-use futures::future::try_join_all;
 use tokio::{
     spawn,
     task::JoinHandle
 };
 use crate::neuron::{
-    NeuronConnectionMap, NeuronConnection, NeuronConnectionSet
+    Activation, MutationKind, NeuronConnectionMap
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Network {
     pub maps: Vec<NeuronConnectionMap>,
 
@@ -29,7 +30,41 @@ pub struct Network {
     pub num_outputs: usize
 }
 
-const NUM_USIZE_BYTES: usize = (usize::BITS / 8) as usize;
+// Bumped whenever the on-disk model layout changes; loading a model with any other version
+// panics rather than silently mis-parsing it (no migration of old models is implemented)
+const MODEL_FORMAT_VERSION: u32 = 2;
+
+// Number of fields on NeuronConnection at this format version, written alongside the
+// version tag; loading does not read or check it
+const NEURON_CONNECTION_FIELD_COUNT: u32 = 7;
+
+// Versioned envelope wrapped around a Network on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ModelFile {
+    version: u32,
+    field_count: u32,
+    network: Network
+}
+
+impl ModelFile {
+    fn wrap(network: Network) -> Self {
+        Self {
+            version: MODEL_FORMAT_VERSION,
+            field_count: NEURON_CONNECTION_FIELD_COUNT,
+            network
+        }
+    }
+
+    fn unwrap(self) -> Network {
+        if self.version != MODEL_FORMAT_VERSION {
+            panic!(
+                "Unsupported model format version {} (expected {}); migration not implemented!",
+                self.version, MODEL_FORMAT_VERSION
+            );
+        }
+        self.network
+    }
+}
 
 impl Network {
     /*
@@ -38,8 +73,8 @@ impl Network {
      */
     pub async fn new_random(
             layer_sizes: Vec<usize>, num_inputs: usize, num_outputs: usize,
-            activation_thresh: f64, trait_swap_chance: f64,
-            weight_mutate_chance: f64, weight_mutate_amount: f64,
+            activation_thresh: f64, activation: Activation, trait_swap_chance: f64,
+            mutation_kind: MutationKind, weight_mutate_chance: f64, weight_mutate_amount: f64,
             offset_mutate_chance: f64, offset_mutate_amount: f64) -> Self {
         let mut handles: Vec<JoinHandle<NeuronConnectionMap>> = Vec::new();
         for i in 0..=layer_sizes.len() {
@@ -47,22 +82,22 @@ impl Network {
                 if i == 0 {
                     NeuronConnectionMap::new_random(
                         layer_sizes[i], num_inputs,
-                        activation_thresh, trait_swap_chance,
-                        weight_mutate_chance, weight_mutate_amount,
+                        activation_thresh, activation, trait_swap_chance,
+                        mutation_kind, weight_mutate_chance, weight_mutate_amount,
                         offset_mutate_chance, offset_mutate_amount
                     )
                 } else if i == layer_sizes.len() {
                     NeuronConnectionMap::new_random(
                         num_outputs, layer_sizes[i - 1],
-                        activation_thresh, trait_swap_chance,
-                        weight_mutate_chance, weight_mutate_amount,
+                        activation_thresh, activation, trait_swap_chance,
+                        mutation_kind, weight_mutate_chance, weight_mutate_amount,
                         offset_mutate_chance, offset_mutate_amount
                     )
                 } else {
                     NeuronConnectionMap::new_random(
                         layer_sizes[i], layer_sizes[i - 1],
-                        activation_thresh, trait_swap_chance,
-                        weight_mutate_chance, weight_mutate_amount,
+                        activation_thresh, activation, trait_swap_chance,
+                        mutation_kind, weight_mutate_chance, weight_mutate_amount,
                         offset_mutate_chance, offset_mutate_amount
                     )
                 }
@@ -85,6 +120,93 @@ impl Network {
         last_bits.clone()
     }
 
+    // Run many independent inferences across CPU cores with rayon
+    pub fn result_batch(&self, inputs: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        inputs.par_iter()
+            .map(|input| block_on(self.result(input)))
+            .collect()
+    }
+
+    // Real-valued counterpart to result() that threads Vec<f64> between layers instead of
+    // bit-packed bytes, unlocking smooth fitness landscapes and gradient-based training
+    pub async fn result_real(&self, inputs: &[f64]) -> Vec<f64> {
+        let mut last_outputs = inputs.to_vec();
+        for map in self.maps.iter() {
+            last_outputs = map.layer_activations_real(&last_outputs).await;
+        }
+        last_outputs
+    }
+
+    // Forward pass that caches, per layer, the pre-activation sum z and post-activation output a
+    async fn forward_with_cache(&self, input: &[f64]) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+        let mut activations: Vec<Vec<f64>> = vec![input.to_vec()];
+        let mut zs: Vec<Vec<f64>> = Vec::new();
+        for map in self.maps.iter() {
+            let prev = activations.last().unwrap();
+            let (z_layer, a_layer) = map.layer_forward_real(prev).await;
+            zs.push(z_layer);
+            activations.push(a_layer);
+        }
+        (zs, activations)
+    }
+
+    // Supervised backpropagation fine-tuning pass over an already-evolved Network
+    pub async fn train(
+            &mut self, inputs: &[Vec<f64>], targets: &[Vec<f64>],
+            lr: f64, epochs: usize) -> f64 {
+        let num_layers = self.maps.len();
+        let mut mse = 0.0;
+        for _ in 0..epochs {
+            let mut epoch_loss = 0.0;
+            for (input, target) in inputs.iter().zip(targets.iter()) {
+                let (zs, activations) = self.forward_with_cache(input).await;
+
+                // Output layer error: delta_L = (a_L - target) * f'(z_L)
+                let mut deltas: Vec<Vec<f64>> = vec![Vec::new(); num_layers];
+                let mut output_deltas = Vec::new();
+                for (i, node) in self.maps[num_layers - 1].map.iter().enumerate() {
+                    let a = activations[num_layers][i];
+                    let z = zs[num_layers - 1][i];
+                    let err = a - target[i];
+                    output_deltas.push(err * node.activation.derivative(z, a));
+                    epoch_loss += err * err;
+                }
+                deltas[num_layers - 1] = output_deltas;
+
+                // Backpropagate: delta_l = (W_{l+1}^T . delta_{l+1}) * f'(z_l)
+                for l in (0..num_layers - 1).rev() {
+                    let mut layer_deltas = Vec::with_capacity(self.maps[l].map.len());
+                    for (j, node) in self.maps[l].map.iter().enumerate() {
+                        let mut err = 0.0;
+                        for (i, next_node) in self.maps[l + 1].map.iter().enumerate() {
+                            err += next_node.conns[j].weight * deltas[l + 1][i];
+                        }
+                        let z = zs[l][j];
+                        let a = activations[l + 1][j];
+                        layer_deltas.push(err * node.activation.derivative(z, a));
+                    }
+                    deltas[l] = layer_deltas;
+                }
+
+                // Apply weight/bias updates: weight -= lr * delta_i * a_j, bias -= lr * delta_i
+                for l in 0..num_layers {
+                    let prev_activations = activations[l].clone();
+                    for (i, node) in self.maps[l].map.iter_mut().enumerate() {
+                        let delta = deltas[l][i];
+                        for (conn, a_j) in node.conns.iter_mut().zip(prev_activations.iter()) {
+                            conn.weight -= lr * delta * a_j;
+                            conn.offset -= lr * delta;
+                        }
+                        // z = sum - activation_thresh, so d(z)/d(thresh) = -1
+                        node.activation_thresh += lr * delta;
+                    }
+                }
+            }
+            mse = epoch_loss / (inputs.len() as f64);
+        }
+        mse
+    }
+
     // Can't be parallelized bc mutation
     pub async fn random_trade(&mut self, other: &mut Self) {
         for i in 0..self.maps.len() {
@@ -99,215 +221,176 @@ impl Network {
         }
     }
 
-    // Don't care to optimize. Performance doesn't really matter
-    pub fn from_file(fname: &str) -> Self {
-        let mut file = File::open(fname).expect("Failed to open model file!");
-
-        // Get layer data
-        let mut num_inputs_data: [u8; NUM_USIZE_BYTES] = [0; NUM_USIZE_BYTES];
-        file.read_exact(&mut num_inputs_data).expect("Failed to load model from file!");
-        let num_inputs = usize::from_be_bytes(num_inputs_data);
-        let mut num_outputs_data: [u8; NUM_USIZE_BYTES] = [0; NUM_USIZE_BYTES];
-        file.read_exact(&mut num_outputs_data).expect("Failed to load model from file!");
-        let num_outputs = usize::from_be_bytes(num_outputs_data);
-        let mut layer_sizes = Vec::new();
-        let mut layer_size_data: [u8; NUM_USIZE_BYTES] = [0; NUM_USIZE_BYTES];
-        file.read_exact(&mut layer_size_data).expect("Failed to load model from file!");
-        while usize::from_be_bytes(layer_size_data) != 0xFFFFFFFFFFFFFFFF {
-            layer_sizes.push(usize::from_be_bytes(layer_size_data));
-            file.read_exact(&mut layer_size_data).expect("Failed to load model from file!");
-        }
+    // Human-readable model format
+    pub fn save_json(&self, fname: &str) {
+        let wrapper = ModelFile::wrap(self.clone());
+        let json = serde_json::to_string_pretty(&wrapper).expect("Failed to serialize model!");
+        fs::write(fname, json).expect("Failed to save model file!");
+    }
 
-        // Construct a big array from the rest
-        let mut big_arr_size = num_inputs * layer_sizes[0];
-        for i in 0..layer_sizes.len() - 1 {
-            big_arr_size += layer_sizes[i] * layer_sizes[i + 1];
-        }
-        big_arr_size += layer_sizes[layer_sizes.len() - 1] * num_outputs;
-        big_arr_size *= 8 * 6; // 8 for wgt, ofst, wgt & ofst chance & amnt
-        for i in 0 as usize..=layer_sizes.len() {
-            let out_layer_size = if i == layer_sizes.len() {
-                num_outputs
+    pub fn load_json(fname: &str) -> Self {
+        let data = fs::read_to_string(fname).expect("Failed to open model file!");
+        let wrapper: ModelFile = serde_json::from_str(&data)
+            .expect("Failed to load model from file!");
+        wrapper.unwrap()
+    }
+
+    // Compact binary model format
+    pub fn save_bincode(&self, fname: &str) {
+        let wrapper = ModelFile::wrap(self.clone());
+        let encoded = bincode::serialize(&wrapper).expect("Failed to serialize model!");
+        fs::write(fname, encoded).expect("Failed to save model file!");
+    }
+
+    pub fn load_bincode(fname: &str) -> Self {
+        let data = fs::read(fname).expect("Failed to open model file!");
+        let wrapper: ModelFile = bincode::deserialize(&data)
+            .expect("Failed to load model from file!");
+        wrapper.unwrap()
+    }
+
+    // Iterate over the layer-to-layer connection maps, for debugging evolved topologies and
+    // writing generic tooling without reaching into `maps` by hand
+    pub fn iter(&self) -> std::slice::Iter<'_, NeuronConnectionMap> {
+        self.maps.iter()
+    }
+
+    // Per-layer table (layer index, input size, output size, connection/param count) plus the
+    // grand total parameter count, for inspecting a loaded model's shape
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{:<7}{:<9}{:<9}{}\n", "Layer", "In", "Out", "Params"
+        );
+        let mut total_params = 0;
+        for (i, map) in self.maps.iter().enumerate() {
+            let in_size = if i == 0 {
+                self.num_inputs
             } else {
-                layer_sizes[i]
+                self.layer_sizes[i - 1]
             };
-            big_arr_size += 2 * 8 * out_layer_size;
+            let out_size = map.map.len();
+            let params: usize = map.map.iter().map(|set| 2 * set.conns.len() + 1).sum();
+            total_params += params;
+            summary.push_str(
+                &format!("{:<7}{:<9}{:<9}{}\n", i, in_size, out_size, params)
+            );
         }
-        let mut big_arr = vec![0; big_arr_size];
+        summary.push_str(&format!("Total params: {}\n", total_params));
+        summary
+    }
+}
 
-        file.read_exact(&mut big_arr).expect("Failed to load model from file!");
+impl<'a> IntoIterator for &'a Network {
+    type Item = &'a NeuronConnectionMap;
+    type IntoIter = std::slice::Iter<'a, NeuronConnectionMap>;
 
-        let mut x = 0;
-        let mut maps = Vec::new();
-        for i in 0 as usize..=layer_sizes.len() {
-            let in_layer_size = if i == 0 {
-                num_inputs
-            } else {
-                layer_sizes[i - 1]
-            };
-            let out_layer_size = if i == layer_sizes.len() {
-                num_outputs
-            } else {
-                layer_sizes[i]
-            };
+    fn into_iter(self) -> Self::IntoIter {
+        self.maps.iter()
+    }
+}
 
-            let mut map = Vec::new();
-            for _ in 0..out_layer_size {
-                let mut conns = Vec::new();
-                for _ in 0..in_layer_size {
-                    let mut weight_data = [0; 8];
-                    for k in 0..8 {
-                        weight_data[k] = big_arr[x];
-                        x += 1;
-                    }
-                    let mut offset_data = [0; 8];
-                    for k in 0..8 {
-                        offset_data[k] = big_arr[x];
-                        x += 1;
-                    }
-                    let mut weight_mutate_chance_data = [0; 8];
-                    for k in 0..8 {
-                        weight_mutate_chance_data[k] = big_arr[x];
-                        x += 1;
-                    }
-                    let mut weight_mutate_amount_data = [0; 8];
-                    for k in 0..8 {
-                        weight_mutate_amount_data[k] = big_arr[x];
-                        x += 1;
-                    }
-                    let mut offset_mutate_chance_data = [0; 8];
-                    for k in 0..8 {
-                        offset_mutate_chance_data[k] = big_arr[x];
-                        x += 1;
-                    }
-                    let mut offset_mutate_amount_data = [0; 8];
-                    for k in 0..8 {
-                        offset_mutate_amount_data[k] = big_arr[x];
-                        x += 1;
-                    }
-                    conns.push(
-                        NeuronConnection {
-                            weight: f64::from_be_bytes(weight_data),
-                            offset: f64::from_be_bytes(offset_data),
-                            weight_mutate_chance: f64::from_be_bytes(weight_mutate_chance_data),
-                            weight_mutate_amount: f64::from_be_bytes(weight_mutate_amount_data),
-                            offset_mutate_chance: f64::from_be_bytes(offset_mutate_chance_data),
-                            offset_mutate_amount: f64::from_be_bytes(offset_mutate_amount_data)
-                        }
-                    );
-                }
-                let mut activation_thresh_data = [0; 8];
-                for k in 0..8 {
-                    activation_thresh_data[k] = big_arr[x];
-                    x += 1;
-                }
-                let mut trait_swap_chance_data = [0; 8];
-                for k in 0..8 {
-                    trait_swap_chance_data[k] = big_arr[x];
-                    x += 1;
-                }
-                map.push(NeuronConnectionSet {
-                    conns,
-                    activation_thresh: f64::from_be_bytes(activation_thresh_data),
-                    trait_swap_chance: f64::from_be_bytes(trait_swap_chance_data),
-                });
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neuron::{
+        NeuronConnection, NeuronConnectionSet
+    };
 
-            maps.push(NeuronConnectionMap {
-                map
-            });
-        }
+    // Single Identity neuron, weight=0, offset=0, thresh=1, target=0: a = -thresh, so
+    // loss should strictly decrease over training (catches the thresh sign bug)
+    #[tokio::test]
+    async fn train_reduces_loss_on_single_neuron() {
+        let mut network = Network {
+            maps: vec![NeuronConnectionMap {
+                map: vec![NeuronConnectionSet {
+                    conns: vec![NeuronConnection {
+                        weight: 0.0,
+                        offset: 0.0,
+                        mutation_kind: MutationKind::Uniform,
+                        weight_mutate_chance: 0.0,
+                        weight_mutate_amount: 0.0,
+                        offset_mutate_chance: 0.0,
+                        offset_mutate_amount: 0.0
+                    }],
+                    activation_thresh: 1.0,
+                    activation: Activation::Identity,
+                    trait_swap_chance: 0.0
+                }]
+            }],
+            layer_sizes: vec![1],
+            num_inputs: 1,
+            num_outputs: 1
+        };
 
-        Self {
-            maps,
-            layer_sizes,
-            num_inputs,
-            num_outputs
-        }
+        let before = network.result_real(&[0.0]).await[0];
+        let before_loss = before * before;
+
+        // train() reports each epoch's loss from before that epoch's update, so a single
+        // epoch would just echo before_loss back; run several to see the trend
+        let after_loss = network.train(&[vec![0.0]], &[vec![0.0]], 0.1, 10).await;
+
+        assert!(
+            after_loss < before_loss,
+            "train() should reduce loss: {} >= {}", after_loss, before_loss
+        );
     }
 
-    // Don't care to optimize. Performance doesn't really matter
-    pub async fn save_model(&self, fname: &str) {
-        if Path::new(fname).exists() {
-            remove_file(fname).unwrap();
+    fn sample_network() -> Network {
+        Network {
+            maps: vec![NeuronConnectionMap {
+                map: vec![NeuronConnectionSet {
+                    conns: vec![NeuronConnection {
+                        weight: 0.3,
+                        offset: -0.2,
+                        mutation_kind: MutationKind::Gaussian,
+                        weight_mutate_chance: 0.1,
+                        weight_mutate_amount: 0.05,
+                        offset_mutate_chance: 0.1,
+                        offset_mutate_amount: 0.05
+                    }],
+                    activation_thresh: 0.5,
+                    activation: Activation::Sigmoid,
+                    trait_swap_chance: 0.2
+                }]
+            }],
+            layer_sizes: vec![1],
+            num_inputs: 1,
+            num_outputs: 1
         }
+    }
 
-        let mut file = File::create(fname).expect("Failed to open model file!");
+    // save_json()/load_json() should round-trip a model unchanged
+    #[test]
+    fn json_round_trips() {
+        let network = sample_network();
+        let path = std::env::temp_dir().join("scratch_genetic_test_model.json");
+        network.save_json(path.to_str().unwrap());
+        let loaded = Network::load_json(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        assert_eq!(loaded, network);
+    }
 
-        // Write a header with the correct layer size stuff
-        file.write_all(&self.num_inputs.to_be_bytes()).expect("Failed to save model file!");
-        file.write_all(&self.num_outputs.to_be_bytes()).expect("Failed to save model file!");
-        for layer_size in self.layer_sizes.iter() {
-            file.write_all(&layer_size.to_be_bytes()).expect("Failed to save model file!");
-        }
-        file.write_all(&(0xFFFFFFFFFFFFFFFF as usize).to_be_bytes())
-            .expect("Failed to save model file!");
+    // save_bincode()/load_bincode() should round-trip a model unchanged
+    #[test]
+    fn bincode_round_trips() {
+        let network = sample_network();
+        let path = std::env::temp_dir().join("scratch_genetic_test_model.bin");
+        network.save_bincode(path.to_str().unwrap());
+        let loaded = Network::load_bincode(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        assert_eq!(loaded, network);
+    }
 
-        // Then construct an array with the correct sizes
-        let mut big_arr_size = self.num_inputs * self.layer_sizes[0];
-        for i in 0..self.layer_sizes.len() - 1 {
-            big_arr_size += self.layer_sizes[i] * self.layer_sizes[i + 1];
-        }
-        big_arr_size += self.layer_sizes[self.layer_sizes.len() - 1] * self.num_outputs;
-        big_arr_size *= 8 * 6; // 8 for weight, offset, weight & offset mutate chance & amount
-        for i in 0 as usize..=self.layer_sizes.len() {
-            let out_layer_size = if i == self.layer_sizes.len() {
-                self.num_outputs
-            } else {
-                self.layer_sizes[i]
-            };
-            big_arr_size += 2 * 8 * out_layer_size;
-        }
-        let mut big_arr = vec![0; big_arr_size];
+    // result_batch() must agree with sequential result() for every input
+    #[tokio::test]
+    async fn result_batch_matches_sequential_result() {
+        let network = sample_network();
+        let inputs = vec![vec![0x00u8], vec![0xFFu8], vec![0x80u8]];
 
-        let mut x = 0;
-        for map in self.maps.iter() {
-            for conns in map.map.iter() {
-                for conn in conns.conns.iter() {
-                    let weight_data = conn.weight.to_be_bytes();
-                    for k in 0..8 {
-                        big_arr[x] = weight_data[k];
-                        x += 1;
-                    }
-                    let offset_data = conn.offset.to_be_bytes();
-                    for k in 0..8 {
-                        big_arr[x] = offset_data[k];
-                        x += 1;
-                    }
-                    let weight_mutate_chance_data = conn.weight_mutate_chance.to_be_bytes();
-                    for k in 0..8 {
-                        big_arr[x] = weight_mutate_chance_data[k];
-                        x += 1;
-                    }
-                    let weight_mutate_amount_data = conn.weight_mutate_amount.to_be_bytes();
-                    for k in 0..8 {
-                        big_arr[x] = weight_mutate_amount_data[k];
-                        x += 1;
-                    }
-                    let offset_mutate_chance_data = conn.offset_mutate_chance.to_be_bytes();
-                    for k in 0..8 {
-                        big_arr[x] = offset_mutate_chance_data[k];
-                        x += 1;
-                    }
-                    let offset_mutate_amount_data = conn.offset_mutate_amount.to_be_bytes();
-                    for k in 0..8 {
-                        big_arr[x] = offset_mutate_amount_data[k];
-                        x += 1;
-                    }
-                }
-                let activation_thresh_data = conns.activation_thresh.to_be_bytes();
-                for k in 0..8 {
-                    big_arr[x] = activation_thresh_data[k];
-                    x += 1;
-                }
-                let trait_swap_chance_data = conns.trait_swap_chance.to_be_bytes();
-                for k in 0..8 {
-                    big_arr[x] = trait_swap_chance_data[k];
-                    x += 1;
-                }
-            }
+        let batched = network.result_batch(&inputs);
+        for (input, batch_output) in inputs.iter().zip(batched.iter()) {
+            let sequential_output = network.result(input).await;
+            assert_eq!(batch_output, &sequential_output);
         }
-
-        file.write_all(&big_arr).expect("Failed to save model to file!");
     }
 }
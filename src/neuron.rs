@@ -6,14 +6,75 @@
 use rand::{
     Rng, thread_rng
 };
+use rand_distr::{
+    Distribution, Normal
+};
+use serde::{
+    Serialize, Deserialize
+};
+
+// Real-valued activation function applied to a neuron's weighted sum
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Activation {
+    Step,
+    Sigmoid,
+    Tanh,
+    ReLU,
+    Identity
+}
+
+impl Activation {
+    // Apply the activation function to a pre-activation sum
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            Activation::Step => if x > 0.0 { 1.0 } else { 0.0 },
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Tanh => x.tanh(),
+            Activation::ReLU => x.max(0.0),
+            Activation::Identity => x
+        }
+    }
+
+    // Derivative of the activation, given its pre-activation sum z and post-activation output a,
+    // used to backpropagate error through a layer during Network::train
+    pub fn derivative(&self, z: f64, a: f64) -> f64 {
+        match self {
+            Activation::Step => 0.0,
+            Activation::Sigmoid => a * (1.0 - a),
+            Activation::Tanh => 1.0 - a * a,
+            Activation::ReLU => if z > 0.0 { 1.0 } else { 0.0 },
+            Activation::Identity => 1.0
+        }
+    }
+}
+
+impl Default for Activation {
+    fn default() -> Self {
+        Activation::Step
+    }
+}
+
+// How a weight/offset is perturbed when it mutates
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MutationKind {
+    Uniform,
+    Gaussian
+}
+
+impl Default for MutationKind {
+    fn default() -> Self {
+        MutationKind::Uniform
+    }
+}
 
 // A neuron doesn't actually exist, only the connections between them
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NeuronConnection {
     pub weight: f64,
     pub offset: f64,
 
     // Tweakable settings
+    pub mutation_kind: MutationKind,
     pub weight_mutate_chance: f64,
     pub weight_mutate_amount: f64,
     pub offset_mutate_chance: f64,
@@ -22,12 +83,14 @@ pub struct NeuronConnection {
 
 impl NeuronConnection {
     pub async fn new_random(
+            mutation_kind: MutationKind,
             weight_mutate_chance: f64, weight_mutate_amount: f64,
             offset_mutate_chance: f64, offset_mutate_amount: f64) -> Self {
         let mut rng = thread_rng();
         Self {
             weight: rng.gen_range(-1.0..=1.0),
             offset: rng.gen_range(-0.5..=0.5),
+            mutation_kind,
             weight_mutate_chance,
             weight_mutate_amount,
             offset_mutate_chance,
@@ -38,25 +101,34 @@ impl NeuronConnection {
     pub async fn mutate(&mut self) {
         let mut rng = thread_rng();
         if rng.gen_bool(self.weight_mutate_chance) {
-            self.weight = rng.gen_range(
-                (self.weight - self.weight_mutate_amount)..(self.weight + self.weight_mutate_amount)
+            self.weight += Self::mutation_delta(
+                self.mutation_kind, self.weight_mutate_amount, &mut rng
             );
         }
         let mut rng = thread_rng();
         if rng.gen_bool(self.offset_mutate_chance) {
-            self.offset = rng.gen_range(
-                (self.offset - self.offset_mutate_amount)..(self.offset + self.offset_mutate_amount)
+            self.offset += Self::mutation_delta(
+                self.mutation_kind, self.offset_mutate_amount, &mut rng
             );
         }
     }
+
+    // Sample a weight/offset delta for the given MutationKind
+    fn mutation_delta(kind: MutationKind, amount: f64, rng: &mut impl Rng) -> f64 {
+        match kind {
+            MutationKind::Uniform => rng.gen_range(-amount..amount),
+            MutationKind::Gaussian => Normal::new(0.0, amount).unwrap().sample(rng)
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NeuronConnectionSet {
     pub conns: Vec<NeuronConnection>,
 
     // Tweakable settings
     pub activation_thresh: f64,
+    pub activation: Activation,
     pub trait_swap_chance: f64
 }
 
@@ -67,19 +139,20 @@ impl NeuronConnectionSet {
      * The activations and rand_gen_neurons could also use something similar, but again, it's slower
      */
     async fn new_random(
-            size: usize, activation_thresh: f64, trait_swap_chance: f64,
-            weight_mutate_chance: f64, weight_mutate_amount: f64,
+            size: usize, activation_thresh: f64, activation: Activation, trait_swap_chance: f64,
+            mutation_kind: MutationKind, weight_mutate_chance: f64, weight_mutate_amount: f64,
             offset_mutate_chance: f64, offset_mutate_amount: f64) -> Self {
         let mut conns = Vec::new();
         for _ in 0..size {
             conns.push(NeuronConnection::new_random(
-                weight_mutate_chance, weight_mutate_amount,
+                mutation_kind, weight_mutate_chance, weight_mutate_amount,
                 offset_mutate_chance, offset_mutate_amount
             ).await);
         }
         Self {
             conns,
             activation_thresh,
+            activation,
             trait_swap_chance
         }
 
@@ -97,7 +170,7 @@ impl NeuronConnectionSet {
         for conn in self.conns.iter() {
             let input = input_bits[byte_ind] >> (7 - bit) & 0x01;
             sum += conn.weight * input as f64 + conn.offset;
-    
+
             // Move throught the input array
             bit += 1;
             if bit == 8 {
@@ -108,6 +181,22 @@ impl NeuronConnectionSet {
         sum > self.activation_thresh
     }
 
+    // Real-valued counterpart to activated()
+    pub async fn activated_real(&self, inputs: &[f64]) -> f64 {
+        let (_, a) = self.forward_real(inputs).await;
+        a
+    }
+
+    // Forward pass that also returns the pre-activation sum z
+    pub async fn forward_real(&self, inputs: &[f64]) -> (f64, f64) {
+        let mut sum: f64 = 0.0;
+        for (conn, input) in self.conns.iter().zip(inputs.iter()) {
+            sum += conn.weight * input + conn.offset;
+        }
+        let z = sum - self.activation_thresh;
+        (z, self.activation.apply(z))
+    }
+
     // Trade with another connection set
     pub async fn trade_with(&mut self, other: &mut Self) {
         self.conns.iter_mut().zip(other.conns.iter_mut()).for_each(|(conn, other_conn)| {
@@ -129,7 +218,7 @@ impl NeuronConnectionSet {
 }
 
 // This is essentially a mapping from one layer to another, so it's a connection
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NeuronConnectionMap {
     pub map: Vec<NeuronConnectionSet>
 }
@@ -141,15 +230,15 @@ impl NeuronConnectionMap {
     */
     pub async fn new_random(
             size: usize, neuron_size: usize,
-            activation_thresh: f64, trait_swap_chance: f64,
-            weight_mutate_chance: f64, weight_mutate_amount: f64,
+            activation_thresh: f64, activation: Activation, trait_swap_chance: f64,
+            mutation_kind: MutationKind, weight_mutate_chance: f64, weight_mutate_amount: f64,
             offset_mutate_chance: f64, offset_mutate_amount: f64) -> Self {
         let mut map = Vec::new();
         for _ in 0..size {
             map.push(NeuronConnectionSet::new_random(
                 neuron_size,
-                activation_thresh, trait_swap_chance,
-                weight_mutate_chance, weight_mutate_amount,
+                activation_thresh, activation, trait_swap_chance,
+                mutation_kind, weight_mutate_chance, weight_mutate_amount,
                 offset_mutate_chance, offset_mutate_amount
             ).await);
         }
@@ -182,6 +271,27 @@ impl NeuronConnectionMap {
         activates
     }
 
+    // Real-valued counterpart to layer_activations()
+    pub async fn layer_activations_real(&self, inputs: &[f64]) -> Vec<f64> {
+        let mut activates = Vec::new();
+        for node in self.map.iter() {
+            activates.push(node.activated_real(inputs).await);
+        }
+        activates
+    }
+
+    // Same as layer_activations_real(), but also returns the per-neuron pre-activation sums z
+    pub async fn layer_forward_real(&self, inputs: &[f64]) -> (Vec<f64>, Vec<f64>) {
+        let mut zs = Vec::new();
+        let mut activates = Vec::new();
+        for node in self.map.iter() {
+            let (z, a) = node.forward_real(inputs).await;
+            zs.push(z);
+            activates.push(a);
+        }
+        (zs, activates)
+    }
+
     // Trade with another map
     pub async fn trade_with(&mut self, other: &mut Self) {
         for (set, other_set) in self.map.iter_mut().zip(other.map.iter_mut()) {